@@ -1,10 +1,18 @@
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-use clap::{self, Parser};
+use clap::{self, Parser, ValueEnum};
 use ic_ledger_core::block::{BlockHeight, EncodedBlock, BlockType};
 use ledger_canister::{CandidBlock, Block, Operation, AccountIdentifier, protobuf::Account};
 use rusqlite::{Connection, Row, params};
 
+mod migrations;
+mod sink;
+mod verify;
+
+use sink::{BlockSink, PostgresSink, SqliteSink};
+
 #[derive(Parser, Debug)]
 #[clap(version, author, about)]
 struct Args {
@@ -13,31 +21,47 @@ struct Args {
     pub source_store_location: PathBuf, // Path is unsized so we need to use PathBuf
 
     #[clap(short = 't', long)]
-    pub target_store_location: PathBuf, // Path is unsized so we need to use PathBuf
+    pub target_store_location: Option<PathBuf>, // Path is unsized so we need to use PathBuf
+
+    #[clap(long, value_enum, default_value_t = TargetKind::Sqlite)]
+    pub target_kind: TargetKind,
+
+    /// PostgreSQL connection string, required when `--target-kind postgres`.
+    #[clap(long)]
+    pub target_connection_string: Option<String>,
+
+    /// Recompute each block's hash and walk the parent-hash chain before
+    /// writing. Blocks are marked verified only once the chain checks pass.
+    #[clap(long)]
+    pub verify_chain: bool,
+
+    /// Number of blocks decoded and committed per transaction.
+    #[clap(long, default_value_t = 1000)]
+    pub batch_size: u64,
+
+    /// After draining the backlog, keep polling the source store for newly
+    /// appended blocks until interrupted (Ctrl-C).
+    #[clap(long)]
+    pub follow: bool,
+
+    /// Seconds to sleep between polls while in `--follow` mode.
+    #[clap(long, default_value_t = 5)]
+    pub poll_interval: u64,
 }
 
-#[derive(Debug)]
-struct DecodedBlock {
-    idx: u64,
-    hash: Vec<u8>,
-    block: Block,
-    verified: bool,
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum TargetKind {
+    Sqlite,
+    Postgres,
 }
 
-fn create_decoded_table(conn: &Connection) {
-    conn.execute(r#"
-        CREATE TABLE IF NOT EXISTS blocks (idx INTEGER NOT NULL PRIMARY KEY,
-                                           hash BLOB NOT NULL,
-                                           parent_hash BLOB,
-                                           memo INTEGER,
-                                           created_at_time DATETIME,
-                                           from_account BLOB,
-                                           to_account BLOB,
-                                           amount INTEGER NOT NULL,
-                                           fee INTEGER,
-                                           timestamp DATETIME,
-                                           verified BOOL)
-    "#, []).unwrap();
+#[derive(Debug)]
+pub struct DecodedBlock {
+    pub idx: u64,
+    pub hash: Vec<u8>,
+    pub encoded: EncodedBlock,
+    pub block: Block,
+    pub verified: bool,
 }
 
 fn last_block(conn: &Connection) -> Option<BlockHeight> {
@@ -46,17 +70,26 @@ fn last_block(conn: &Connection) -> Option<BlockHeight> {
     x
 }
 
+fn source_block(conn: &Connection, idx: u64) -> DecodedBlock {
+    conn.query_row(
+        "SELECT hash, block, parent_hash, idx, verified FROM blocks WHERE idx = ?",
+        params![idx],
+        row_to_decoded_block,
+    ).unwrap()
+}
+
 fn row_to_decoded_block(row: &Row) -> rusqlite::Result<DecodedBlock> {
     // hash, block, parent_hash, idx, verified
     let idx: u64 = row.get(row.column_index("idx").unwrap()).unwrap();
     let hash: Vec<u8> = row.get(row.column_index("hash").unwrap()).unwrap();
     let block: Vec<u8> = row.get(row.column_index("block").unwrap()).unwrap();
-    let block = <Block as BlockType>::decode(EncodedBlock::from(block)).unwrap();
+    let encoded = EncodedBlock::from(block);
+    let block = <Block as BlockType>::decode(encoded.clone()).unwrap();
     let verified = row.get(row.column_index("verified").unwrap()).unwrap();
-    Ok(DecodedBlock { idx, hash, block, verified })
+    Ok(DecodedBlock { idx, hash, encoded, block, verified })
 }
 
-fn from(op: &Operation) -> Option<Vec<u8>> {
+pub fn from(op: &Operation) -> Option<Vec<u8>> {
     match op {
         Operation::Burn { from, .. } => Some(from.to_vec()),
         Operation::Mint { .. } => None,
@@ -64,7 +97,7 @@ fn from(op: &Operation) -> Option<Vec<u8>> {
     }
 }
 
-fn to(op: &Operation) -> Option<Vec<u8>> {
+pub fn to(op: &Operation) -> Option<Vec<u8>> {
     match op {
         Operation::Burn { .. } => None,
         Operation::Mint { to, .. } => Some(to.to_vec()),
@@ -72,7 +105,7 @@ fn to(op: &Operation) -> Option<Vec<u8>> {
     }
 }
 
-fn amount(op: &Operation) -> u64 {
+pub fn amount(op: &Operation) -> u64 {
     match op {
         Operation::Burn { amount, .. } => amount.get_e8s(),
         Operation::Mint { amount, .. } => amount.get_e8s(),
@@ -80,7 +113,7 @@ fn amount(op: &Operation) -> u64 {
     }
 }
 
-fn fee(op: &Operation) -> Option<u64> {
+pub fn fee(op: &Operation) -> Option<u64> {
     match op {
         Operation::Burn { .. } => None,
         Operation::Mint { .. } => None,
@@ -88,50 +121,104 @@ fn fee(op: &Operation) -> Option<u64> {
     }
 }
 
+fn build_sink(args: &Args) -> Box<dyn BlockSink> {
+    match args.target_kind {
+        TargetKind::Sqlite => {
+            let location = args.target_store_location.clone()
+                .expect("--target-store-location is required for the sqlite target");
+            Box::new(SqliteSink::open(location))
+        }
+        TargetKind::Postgres => {
+            let conn_str = args.target_connection_string.as_deref()
+                .expect("--target-connection-string is required for the postgres target");
+            Box::new(PostgresSink::connect(conn_str))
+        }
+    }
+}
+
+/// Decode and persist every source block in `next_target_block..=last_source_block`,
+/// one batch-sized transaction at a time, returning the next index still to decode.
+fn decode_backlog(
+    source_conn: &Connection,
+    sink: &mut dyn BlockSink,
+    mut verifier: Option<&mut verify::ChainVerifier>,
+    next_target_block: BlockHeight,
+    last_source_block: BlockHeight,
+    batch_size: u64,
+) -> BlockHeight {
+    for start in (next_target_block..=last_source_block).step_by(batch_size as usize) {
+        let end = std::cmp::min(start + batch_size, last_source_block + 1);
+        let mut stmt = source_conn.prepare("SELECT hash, block, parent_hash, idx, verified FROM blocks WHERE idx >= ? AND idx < ?").unwrap();
+        let mut blocks: Vec<DecodedBlock> = stmt.query_map(params![start, end], row_to_decoded_block).unwrap()
+            .map(|block| block.unwrap())
+            .collect();
+        if let Some(verifier) = verifier.as_deref_mut() {
+            for block in blocks.iter_mut() {
+                verifier.verify(block);
+                block.verified = true;
+            }
+        }
+        sink.insert_blocks(&blocks);
+    }
+    last_source_block + 1
+}
+
 fn main() {
     let args = Args::parse();
     let source_conn = Connection::open(args.source_store_location.clone()).unwrap();
-    let target_conn = Connection::open(args.target_store_location).unwrap();
-    create_decoded_table(&target_conn);
-    let next_target_block = last_block(&target_conn).map_or(0, |x| x + 1);
-    let last_source_block = match last_block(&source_conn) {
-        Some(last_source_block) if last_source_block < next_target_block => {
-            println!("All blocks decoded. Last block {}", last_source_block);
-            return;
-        } 
-        Some(last_source_block) => last_source_block,
-        None => {
-            println!("Source table at {:#?} is empty", args.source_store_location);
-            return;
-        },
+    let mut sink = build_sink(&args);
+    sink.create_schema();
+    let mut next_target_block = sink.last_block().map_or(0, |x| x + 1);
+
+    // When resuming mid-chain, seed the verifier with the recomputed hash of
+    // the last already-decoded block so continuity keeps being checked.
+    let mut verifier = if args.verify_chain {
+        let prev_hash = next_target_block.checked_sub(1).map(|idx| {
+            let block = source_block(&source_conn, idx);
+            verify::recompute_hash(&block.encoded)
+        });
+        Some(verify::ChainVerifier::new(next_target_block, prev_hash))
+    } else {
+        None
     };
 
-    for start in (next_target_block..=last_source_block).step_by(1000) {
-        let end = start + 1000;
-        let mut stmt = source_conn.prepare("SELECT hash, block, parent_hash, idx, verified FROM blocks WHERE idx >= ? AND idx < ?").unwrap();
-        let blocks = stmt.query_map(params![start, end], row_to_decoded_block).unwrap();
-        let mut stmt = target_conn.prepare(r#"
-            INSERT INTO blocks (
-                idx, hash, parent_hash, memo, created_at_time,
-                from_account, to_account, amount, fee, timestamp, verified
-            ) values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
-        "#).unwrap();
-        for block in blocks {
-            let block = block.unwrap();
-            stmt.execute(params![
-                block.idx,
-                block.hash,
-                block.block.parent_hash.map(|h| h.as_slice().to_vec()),
-                block.block.transaction.memo.0,
-                block.block.transaction.created_at_time.as_nanos_since_unix_epoch() as f64 / 1_000_000_000f64,
-                from(&block.block.transaction.operation),
-                to(&block.block.transaction.operation),
-                amount(&block.block.transaction.operation),
-                fee(&block.block.transaction.operation),
-                block.block.timestamp.as_nanos_since_unix_epoch() as f64 / 1_000_000_000f64,
-                block.verified,
-            ]).expect(&format!("Unable to write block {:#?}", block));
+    // In follow mode we keep running until Ctrl-C; the handler flips this flag
+    // and the poll loop finishes the decode pass in progress (every batch up to
+    // the source's last block) before exiting cleanly.
+    let running = Arc::new(AtomicBool::new(true));
+    if args.follow {
+        let running = running.clone();
+        ctrlc::set_handler(move || running.store(false, Ordering::SeqCst))
+            .expect("unable to install SIGINT handler");
+    }
+
+    loop {
+        match last_block(&source_conn) {
+            Some(last_source_block) if last_source_block >= next_target_block => {
+                next_target_block = decode_backlog(
+                    &source_conn,
+                    sink.as_mut(),
+                    verifier.as_mut(),
+                    next_target_block,
+                    last_source_block,
+                    args.batch_size,
+                );
+            }
+            Some(last_source_block) if !args.follow => {
+                println!("All blocks decoded. Last block {}", last_source_block);
+                break;
+            }
+            None if !args.follow => {
+                println!("Source table at {:#?} is empty", args.source_store_location);
+                break;
+            }
+            _ => {}
+        }
+
+        if !args.follow || !running.load(Ordering::SeqCst) {
+            break;
         }
+        std::thread::sleep(std::time::Duration::from_secs(args.poll_interval));
     }
 
     println!("next_target_block: {:#?}", next_target_block);
@@ -0,0 +1,82 @@
+use rusqlite::Connection;
+
+/// A single, ordered step that brings the target store from schema version
+/// `id - 1` up to `id`. Migrations are append-only: once a version has shipped
+/// its `up` closure must never change, only later ids are added. Each `up`
+/// runs inside the transaction opened by [`run_migrations`], so a step that
+/// returns an error rolls the whole version back.
+pub struct Migration {
+    pub id: u32,
+    pub description: &'static str,
+    pub up: fn(&Connection) -> rusqlite::Result<()>,
+}
+
+/// The ordered list of migrations describing the decoded-blocks schema.
+///
+/// Version 1 is the original `blocks` table that used to live in
+/// `create_decoded_table`; later versions evolve the schema in place so an
+/// already-populated target store never has to be re-decoded from source.
+fn migrations() -> Vec<Migration> {
+    vec![
+        Migration {
+            id: 1,
+            description: "initial blocks table",
+            up: |conn| {
+                conn.execute(r#"
+                    CREATE TABLE IF NOT EXISTS blocks (idx INTEGER NOT NULL PRIMARY KEY,
+                                                       hash BLOB NOT NULL,
+                                                       parent_hash BLOB,
+                                                       memo INTEGER,
+                                                       created_at_time DATETIME,
+                                                       from_account BLOB,
+                                                       to_account BLOB,
+                                                       amount INTEGER NOT NULL,
+                                                       fee INTEGER,
+                                                       timestamp DATETIME,
+                                                       verified BOOL)
+                "#, [])?;
+                Ok(())
+            },
+        },
+        Migration {
+            id: 2,
+            description: "normalized accounts dimension with query indexes",
+            up: |conn| {
+                conn.execute(r#"
+                    CREATE TABLE IF NOT EXISTS accounts (account_id INTEGER PRIMARY KEY,
+                                                         account_hash BLOB UNIQUE)
+                "#, [])?;
+                conn.execute("ALTER TABLE blocks ADD COLUMN from_account_id INTEGER", [])?;
+                conn.execute("ALTER TABLE blocks ADD COLUMN to_account_id INTEGER", [])?;
+                conn.execute("CREATE INDEX IF NOT EXISTS idx_blocks_from_account_id ON blocks (from_account_id)", [])?;
+                conn.execute("CREATE INDEX IF NOT EXISTS idx_blocks_to_account_id ON blocks (to_account_id)", [])?;
+                conn.execute("CREATE INDEX IF NOT EXISTS idx_blocks_timestamp ON blocks (timestamp)", [])?;
+                Ok(())
+            },
+        },
+    ]
+}
+
+fn current_version(conn: &Connection) -> u32 {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)",
+        [],
+    ).unwrap();
+    conn.query_row("SELECT MAX(version) FROM schema_version", [], |row| {
+        Ok(row.get::<_, Option<u32>>(0)?.unwrap_or(0))
+    }).unwrap()
+}
+
+/// Bring the target store up to the latest schema version, applying every
+/// pending migration in ascending id order. Each step runs in its own
+/// transaction and bumps `schema_version` only once its `up` succeeds.
+pub fn run_migrations(conn: &Connection) {
+    let version = current_version(conn);
+    for migration in migrations().into_iter().filter(|m| m.id > version) {
+        let tx = conn.unchecked_transaction().unwrap();
+        (migration.up)(&tx)
+            .unwrap_or_else(|e| panic!("migration {} ({}) failed: {}", migration.id, migration.description, e));
+        tx.execute("INSERT INTO schema_version (version) VALUES (?)", [migration.id]).unwrap();
+        tx.commit().unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+use ic_ledger_core::block::BlockHeight;
+use rusqlite::{params, Connection};
+
+use crate::{amount, fee, from, migrations, to, DecodedBlock};
+
+/// A write target for decoded blocks. The decode loop in `main` is agnostic to
+/// where the blocks land: it creates the schema once, asks for the highest
+/// already-persisted block to resume from, and streams batches in.
+pub trait BlockSink {
+    /// Ensure the target schema exists and is up to date.
+    fn create_schema(&mut self);
+
+    /// Highest verified block index already persisted, used to resume.
+    fn last_block(&mut self) -> Option<BlockHeight>;
+
+    /// Persist a batch of decoded blocks.
+    fn insert_blocks(&mut self, blocks: &[DecodedBlock]);
+}
+
+/// The original rusqlite-backed target. Schema evolution is handled by the
+/// versioned migration subsystem; see [`migrations`].
+pub struct SqliteSink {
+    conn: Connection,
+}
+
+impl SqliteSink {
+    pub fn open(location: std::path::PathBuf) -> Self {
+        SqliteSink { conn: Connection::open(location).unwrap() }
+    }
+
+}
+
+/// Resolve an account hash to its `accounts` id, inserting on first sight.
+fn resolve_account(conn: &Connection, hash: Option<Vec<u8>>) -> Option<i64> {
+    let hash = hash?;
+    conn.execute("INSERT OR IGNORE INTO accounts (account_hash) VALUES (?)", params![hash]).unwrap();
+    let id: i64 = conn.query_row(
+        "SELECT account_id FROM accounts WHERE account_hash = ?",
+        params![hash],
+        |row| row.get(0),
+    ).unwrap();
+    Some(id)
+}
+
+impl BlockSink for SqliteSink {
+    fn create_schema(&mut self) {
+        migrations::run_migrations(&self.conn);
+    }
+
+    fn last_block(&mut self) -> Option<BlockHeight> {
+        self.conn.query_row("SELECT MAX(idx) FROM blocks WHERE verified = 1", [], |row| row.get(0)).unwrap()
+    }
+
+    fn insert_blocks(&mut self, blocks: &[DecodedBlock]) {
+        // One transaction per batch: either the whole batch lands or none of
+        // it does, so a crash mid-batch leaves the store resumable from the
+        // last committed batch. The INSERT statement is prepared once and
+        // reused across every block in the batch.
+        let tx = self.conn.transaction().unwrap();
+        {
+            let mut stmt = tx.prepare(r#"
+                INSERT INTO blocks (
+                    idx, hash, parent_hash, memo, created_at_time,
+                    from_account_id, to_account_id, amount, fee, timestamp, verified
+                ) values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            "#).unwrap();
+            for block in blocks {
+                let from_account_id = resolve_account(&tx, from(&block.block.transaction.operation));
+                let to_account_id = resolve_account(&tx, to(&block.block.transaction.operation));
+                stmt.execute(params![
+                    block.idx,
+                    block.hash,
+                    block.block.parent_hash.map(|h| h.as_slice().to_vec()),
+                    block.block.transaction.memo.0,
+                    block.block.transaction.created_at_time.as_nanos_since_unix_epoch() as f64 / 1_000_000_000f64,
+                    from_account_id,
+                    to_account_id,
+                    amount(&block.block.transaction.operation),
+                    fee(&block.block.transaction.operation),
+                    block.block.timestamp.as_nanos_since_unix_epoch() as f64 / 1_000_000_000f64,
+                    block.verified,
+                ]).expect(&format!("Unable to write block {:#?}", block));
+            }
+        }
+        tx.commit().unwrap();
+    }
+}
+
+/// A PostgreSQL-backed target for users who want the decoded blocks sitting
+/// next to their Grafana/dashboard stack. It mirrors the SQLite schema: a
+/// normalized `accounts` dimension plus a `blocks` fact table with the same
+/// per-account and timestamp indexes.
+pub struct PostgresSink {
+    client: postgres::Client,
+}
+
+impl PostgresSink {
+    pub fn connect(connection_string: &str) -> Self {
+        let client = postgres::Client::connect(connection_string, postgres::NoTls).unwrap();
+        PostgresSink { client }
+    }
+}
+
+impl BlockSink for PostgresSink {
+    fn create_schema(&mut self) {
+        self.client.batch_execute(r#"
+            CREATE TABLE IF NOT EXISTS accounts (account_id BIGSERIAL PRIMARY KEY,
+                                                 account_hash BYTEA UNIQUE);
+            CREATE TABLE IF NOT EXISTS blocks (idx BIGINT NOT NULL PRIMARY KEY,
+                                               hash BYTEA NOT NULL,
+                                               parent_hash BYTEA,
+                                               memo BIGINT,
+                                               created_at_time DOUBLE PRECISION,
+                                               from_account_id BIGINT,
+                                               to_account_id BIGINT,
+                                               amount BIGINT NOT NULL,
+                                               fee BIGINT,
+                                               timestamp DOUBLE PRECISION,
+                                               verified BOOLEAN);
+            CREATE INDEX IF NOT EXISTS idx_blocks_from_account_id ON blocks (from_account_id);
+            CREATE INDEX IF NOT EXISTS idx_blocks_to_account_id ON blocks (to_account_id);
+            CREATE INDEX IF NOT EXISTS idx_blocks_timestamp ON blocks (timestamp);
+        "#).unwrap();
+    }
+
+    fn last_block(&mut self) -> Option<BlockHeight> {
+        let row = self.client.query_one("SELECT MAX(idx) FROM blocks WHERE verified = true", &[]).unwrap();
+        let max: Option<i64> = row.get(0);
+        max.map(|v| v as BlockHeight)
+    }
+
+    fn insert_blocks(&mut self, blocks: &[DecodedBlock]) {
+        let mut tx = self.client.transaction().unwrap();
+        for block in blocks {
+            let from_account_id = resolve_account_tx(&mut tx, from(&block.block.transaction.operation));
+            let to_account_id = resolve_account_tx(&mut tx, to(&block.block.transaction.operation));
+            tx.execute(r#"
+                INSERT INTO blocks (
+                    idx, hash, parent_hash, memo, created_at_time,
+                    from_account_id, to_account_id, amount, fee, timestamp, verified
+                ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            "#, &[
+                &(block.idx as i64),
+                &block.hash,
+                &block.block.parent_hash.map(|h| h.as_slice().to_vec()),
+                &(block.block.transaction.memo.0 as i64),
+                &(block.block.transaction.created_at_time.as_nanos_since_unix_epoch() as f64 / 1_000_000_000f64),
+                &from_account_id,
+                &to_account_id,
+                &(amount(&block.block.transaction.operation) as i64),
+                &fee(&block.block.transaction.operation).map(|f| f as i64),
+                &(block.block.timestamp.as_nanos_since_unix_epoch() as f64 / 1_000_000_000f64),
+                &block.verified,
+            ]).unwrap();
+        }
+        tx.commit().unwrap();
+    }
+}
+
+fn resolve_account_tx(tx: &mut postgres::Transaction<'_>, hash: Option<Vec<u8>>) -> Option<i64> {
+    let hash = hash?;
+    tx.execute(
+        "INSERT INTO accounts (account_hash) VALUES ($1) ON CONFLICT (account_hash) DO NOTHING",
+        &[&hash],
+    ).unwrap();
+    let row = tx.query_one(
+        "SELECT account_id FROM accounts WHERE account_hash = $1",
+        &[&hash],
+    ).unwrap();
+    Some(row.get(0))
+}
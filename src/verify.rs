@@ -0,0 +1,48 @@
+use ic_ledger_core::block::{BlockType, EncodedBlock};
+use ledger_canister::Block;
+
+use crate::DecodedBlock;
+
+/// Recompute a block's hash from its encoded bytes (SHA-256 over the
+/// `EncodedBlock`, as the ledger does), returning the raw digest bytes.
+pub fn recompute_hash(encoded: &EncodedBlock) -> Vec<u8> {
+    Block::block_hash(encoded).as_slice().to_vec()
+}
+
+/// Walks the decoded chain in index order, recomputing each block's hash and
+/// checking it links to the previous block. It carries the previously computed
+/// hash across batch boundaries so a resumed run keeps checking continuity.
+pub struct ChainVerifier {
+    prev_hash: Option<Vec<u8>>,
+    next_idx: u64,
+}
+
+impl ChainVerifier {
+    /// Start verifying at `start_idx`. `prev_hash` is the recomputed hash of
+    /// block `start_idx - 1` when resuming mid-chain, or `None` when starting
+    /// from genesis.
+    pub fn new(start_idx: u64, prev_hash: Option<Vec<u8>>) -> Self {
+        ChainVerifier { prev_hash, next_idx: start_idx }
+    }
+
+    /// Verify one block, panicking with the offending `idx` on any mismatch.
+    /// Genesis must have no parent; every later block's `parent_hash` must
+    /// equal the previous block's recomputed hash, and the recomputed hash must
+    /// match the `hash` stored in the source row.
+    pub fn verify(&mut self, block: &DecodedBlock) {
+        assert_eq!(block.idx, self.next_idx, "out-of-order block: expected idx {}, got {}", self.next_idx, block.idx);
+
+        let parent_hash = block.block.parent_hash.map(|h| h.as_slice().to_vec());
+        if self.next_idx == 0 {
+            assert!(parent_hash.is_none(), "block 0 must have no parent_hash");
+        } else {
+            assert_eq!(parent_hash, self.prev_hash, "parent_hash mismatch at idx {}", block.idx);
+        }
+
+        let computed = recompute_hash(&block.encoded);
+        assert_eq!(computed, block.hash, "recomputed hash mismatch at idx {}", block.idx);
+
+        self.prev_hash = Some(computed);
+        self.next_idx += 1;
+    }
+}